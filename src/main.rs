@@ -1,22 +1,133 @@
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{self, BufRead, BufReader, Read, Write};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
 use std::net::{TcpListener, TcpStream};
-use std::path::PathBuf;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Component, Path, PathBuf};
 use std::thread;
 
 use clap::Parser;
+use flate2::write::{DeflateEncoder, GzEncoder};
+use flate2::Compression;
 
-use std::sync::Arc;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
 
 // === Request ===
+#[derive(Debug, Clone, Copy)]
+pub enum RequestRange {
+    From(usize),
+    Full(usize, usize),
+    Suffix(usize),
+}
+
+fn parse_range(value: &str) -> Option<RequestRange> {
+    let spec = value.strip_prefix("bytes=")?;
+    let (start_str, end_str) = spec.split_once('-')?;
+    if start_str.is_empty() {
+        let suffix_len = end_str.parse::<usize>().ok()?;
+        Some(RequestRange::Suffix(suffix_len))
+    } else {
+        let start = start_str.parse::<usize>().ok()?;
+        if end_str.is_empty() {
+            Some(RequestRange::From(start))
+        } else {
+            let end = end_str.parse::<usize>().ok()?;
+            Some(RequestRange::Full(start, end))
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Request {
     pub method: String,
     pub path: Vec<String>,
     pub version: String,
     pub headers: HashMap<String, String>,
-    pub body: String,
+    pub body: Vec<u8>,
+    pub range: Option<RequestRange>,
+    pub params: HashMap<String, String>,
+}
+
+/// Decodes a `Transfer-Encoding: chunked` body: repeatedly reads a hex
+/// chunk-size line (optionally followed by `;ext` extensions, which are
+/// ignored), then that many bytes plus the trailing CRLF, stopping at the
+/// zero-size chunk and consuming any trailer header block that follows it.
+fn read_chunked_body(reader: &mut BufReader<&TcpStream>) -> Option<Vec<u8>> {
+    let mut body = Vec::new();
+
+    loop {
+        let mut size_line = String::new();
+        reader.read_line(&mut size_line).ok()?;
+        let size_str = size_line.trim_end().split(';').next()?.trim();
+        let size = usize::from_str_radix(size_str, 16).ok()?;
+
+        if size == 0 {
+            let mut trailer = String::new();
+            while reader.read_line(&mut trailer).ok()? > 0 {
+                if trailer.trim_end().is_empty() {
+                    break;
+                }
+                trailer.clear();
+            }
+            break;
+        }
+
+        let mut chunk = vec![0; size];
+        reader.read_exact(&mut chunk).ok()?;
+        body.extend_from_slice(&chunk);
+
+        let mut crlf = [0u8; 2];
+        reader.read_exact(&mut crlf).ok()?;
+    }
+
+    Some(body)
+}
+
+#[cfg(test)]
+mod chunked_body_tests {
+    use super::*;
+
+    /// Feeds `input` through a loopback TCP connection and decodes it, since
+    /// `read_chunked_body` reads from a live `TcpStream`.
+    fn decode(input: &[u8]) -> Option<Vec<u8>> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(input).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(&server);
+        read_chunked_body(&mut reader)
+    }
+
+    #[test]
+    fn decodes_multiple_chunks() {
+        assert_eq!(
+            decode(b"3\r\nfoo\r\n3\r\nbar\r\n0\r\n\r\n"),
+            Some(b"foobar".to_vec())
+        );
+    }
+
+    #[test]
+    fn tolerates_chunk_extensions() {
+        assert_eq!(
+            decode(b"5;ext=value\r\nhello\r\n0\r\n\r\n"),
+            Some(b"hello".to_vec())
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_hex_size() {
+        assert_eq!(decode(b"zz\r\nhello\r\n0\r\n\r\n"), None);
+    }
+
+    #[test]
+    fn consumes_trailer_headers_after_final_chunk() {
+        assert_eq!(
+            decode(b"5\r\nhello\r\n0\r\nX-Trailer: value\r\n\r\n"),
+            Some(b"hello".to_vec())
+        );
+    }
 }
 
 impl Request {
@@ -50,17 +161,32 @@ impl Request {
             line.clear();
         }
 
-        // Read body if Content-Length exists
-        let content_length = headers
+        // Transfer-Encoding: chunked takes priority over Content-Length.
+        let is_chunked = headers
             .iter()
-            .find(|(k, _)| k.eq_ignore_ascii_case("Content-Length"))
-            .and_then(|(_, v)| v.parse::<usize>().ok())
-            .unwrap_or(0);
+            .find(|(k, _)| k.eq_ignore_ascii_case("Transfer-Encoding"))
+            .map_or(false, |(_, v)| {
+                v.split(',').any(|enc| enc.trim().eq_ignore_ascii_case("chunked"))
+            });
 
-        let mut body_buf = vec![0; content_length];
-        reader.read_exact(&mut body_buf).ok()?;
+        let body = if is_chunked {
+            read_chunked_body(&mut reader)?
+        } else {
+            let content_length = headers
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case("Content-Length"))
+                .and_then(|(_, v)| v.parse::<usize>().ok())
+                .unwrap_or(0);
+
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).ok()?;
+            body
+        };
 
-        let body = String::from_utf8_lossy(&body_buf).to_string();
+        let range = headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case("Range"))
+            .and_then(|(_, v)| parse_range(v));
 
         Some(Self {
             method,
@@ -68,58 +194,595 @@ impl Request {
             version,
             headers,
             body,
+            range,
+            params: HashMap::new(),
         })
     }
 }
 
+const STREAM_CHUNK_SIZE: usize = 8 * 1024;
+
+#[derive(Debug)]
+enum ResponseBody {
+    Bytes(Vec<u8>),
+    File(File, usize),
+}
+
 #[derive(Debug)]
 pub struct Response {
     pub status_code: String,
     pub headers: HashMap<String, String>,
-    pub body: String,
+    body: ResponseBody,
+    /// Whether `maybe_compress` is allowed to gzip/deflate this body. Set to
+    /// `false` for content that's already compressed (images, archives,
+    /// video, ...), where compressing again just burns CPU for no size win.
+    compressible: bool,
 }
 
 impl Response {
     pub fn new(status_code: &str, body: &str) -> Self {
+        Self::new_bytes(status_code, body.as_bytes().to_vec())
+            .with_content_type("text/plain")
+    }
+
+    /// Builds a response whose body is served straight from `bytes`, tagged
+    /// `application/octet-stream` by default.
+    pub fn new_bytes(status_code: &str, bytes: Vec<u8>) -> Self {
         let mut obj = Self {
             status_code: status_code.to_string(),
-            body: body.to_string(),
             headers: HashMap::new(),
+            body: ResponseBody::Bytes(Vec::new()),
+            compressible: true,
         };
+        obj.headers
+            .insert("Content-Type".to_string(), "application/octet-stream".to_string());
+        obj.headers
+            .insert("Content-Length".to_string(), bytes.len().to_string());
+        obj.body = ResponseBody::Bytes(bytes);
+        obj
+    }
 
+    /// Builds a response whose body is streamed directly from `file` when
+    /// sent, so large files never need to be buffered in memory up front.
+    /// `len` is the number of bytes to send starting at the file's current
+    /// seek position.
+    pub fn new_file(status_code: &str, file: File, len: usize) -> Self {
+        let mut obj = Self {
+            status_code: status_code.to_string(),
+            headers: HashMap::new(),
+            body: ResponseBody::File(file, len),
+            compressible: true,
+        };
         obj.headers
-            .insert("Content-Type".to_string(), "text/plain".to_string());
+            .insert("Content-Type".to_string(), "application/octet-stream".to_string());
         obj.headers
-            .insert("Content-Length".to_string(), body.len().to_string());
+            .insert("Content-Length".to_string(), len.to_string());
+        obj
+    }
+
+    /// Marks the body as already compressed (or otherwise not worth
+    /// compressing), so `maybe_compress` leaves it alone.
+    pub fn set_compressible(&mut self, compressible: bool) {
+        self.compressible = compressible;
+    }
 
-        return obj;
+    fn with_content_type(mut self, content_type: &str) -> Self {
+        self.headers
+            .insert("Content-Type".to_string(), content_type.to_string());
+        self
     }
 
     pub fn add_header(&mut self, key: String, value: String) {
         self.headers.insert(key, value);
     }
 
+    /// Compresses the body with gzip or deflate when `req` advertises
+    /// support via `Accept-Encoding` and the body is large enough to
+    /// benefit. Leaves partial-content responses alone, since a byte range
+    /// is the exact slice the client asked for.
+    pub fn maybe_compress(&mut self, req: &Request) {
+        const MIN_COMPRESS_SIZE: usize = 1024;
+
+        if self.status_code == "206 Partial Content" || !self.compressible {
+            return;
+        }
+
+        let Some(encoding) = accepted_encoding(req) else {
+            return;
+        };
+
+        if let ResponseBody::File(file, len) = &mut self.body {
+            if *len < MIN_COMPRESS_SIZE {
+                return;
+            }
+            let mut buf = Vec::with_capacity(*len);
+            if file.read_to_end(&mut buf).is_err() {
+                return;
+            }
+            self.body = ResponseBody::Bytes(buf);
+        }
+
+        let ResponseBody::Bytes(bytes) = &self.body else {
+            unreachable!("file bodies are converted to bytes above")
+        };
+        if bytes.len() < MIN_COMPRESS_SIZE {
+            return;
+        }
+
+        let Some(compressed) = compress(bytes, encoding) else {
+            return;
+        };
+
+        self.headers
+            .insert("Content-Encoding".to_string(), encoding.to_string());
+        self.headers
+            .insert("Vary".to_string(), "Accept-Encoding".to_string());
+        self.headers
+            .insert("Content-Length".to_string(), compressed.len().to_string());
+        self.body = ResponseBody::Bytes(compressed);
+    }
+
     pub fn send(&self, mut stream: &TcpStream) {
-        let mut response = format!("HTTP/1.1 {}\r\n", self.status_code);
+        let mut header_block = format!("HTTP/1.1 {}\r\n", self.status_code);
         for (k, v) in &self.headers {
-            response.push_str(&format!("{}: {}\r\n", k, v));
+            header_block.push_str(&format!("{}: {}\r\n", k, v));
+        }
+        header_block.push_str("\r\n");
+        if stream.write_all(header_block.as_bytes()).is_err() {
+            return;
+        }
+
+        match &self.body {
+            ResponseBody::Bytes(bytes) => {
+                for chunk in bytes.chunks(STREAM_CHUNK_SIZE) {
+                    if stream.write_all(chunk).is_err() {
+                        return;
+                    }
+                }
+            }
+            ResponseBody::File(file, len) => {
+                let Ok(mut file) = file.try_clone() else {
+                    return;
+                };
+                let mut remaining = *len;
+                let mut buf = [0u8; STREAM_CHUNK_SIZE];
+                while remaining > 0 {
+                    let to_read = remaining.min(STREAM_CHUNK_SIZE);
+                    match file.read(&mut buf[..to_read]) {
+                        Ok(0) => break,
+                        Ok(n) => {
+                            if stream.write_all(&buf[..n]).is_err() {
+                                return;
+                            }
+                            remaining -= n;
+                        }
+                        Err(_) => return,
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// File extensions whose contents are already compressed (images, archives,
+/// audio/video, ...), where running them through gzip/deflate again just
+/// burns CPU without shrinking the response.
+const PRECOMPRESSED_EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "png", "gif", "webp", "bmp", "ico", "avif", "zip", "gz", "tgz", "bz2", "xz",
+    "7z", "rar", "zst", "mp3", "mp4", "mov", "avi", "mkv", "webm", "ogg", "flac", "pdf", "woff",
+    "woff2",
+];
+
+fn is_precompressed(path: &str) -> bool {
+    PathBuf::from(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| PRECOMPRESSED_EXTENSIONS.iter().any(|known| known.eq_ignore_ascii_case(ext)))
+        .unwrap_or(false)
+}
+
+/// Picks gzip over deflate when a request's `Accept-Encoding` lists both,
+/// and `None` when it lists neither.
+fn accepted_encoding(req: &Request) -> Option<&'static str> {
+    let value = req
+        .headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case("Accept-Encoding"))
+        .map(|(_, v)| v.as_str())?;
+    let encodings: Vec<&str> = value
+        .split(',')
+        .map(|e| e.split(';').next().unwrap_or(e).trim())
+        .collect();
+
+    if encodings.iter().any(|e| e.eq_ignore_ascii_case("gzip")) {
+        Some("gzip")
+    } else if encodings.iter().any(|e| e.eq_ignore_ascii_case("deflate")) {
+        Some("deflate")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod accepted_encoding_tests {
+    use super::*;
+
+    fn request_with_accept_encoding(value: &str) -> Request {
+        let mut headers = HashMap::new();
+        headers.insert("Accept-Encoding".to_string(), value.to_string());
+        Request {
+            method: "GET".to_string(),
+            path: Vec::new(),
+            version: "HTTP/1.1".to_string(),
+            headers,
+            body: Vec::new(),
+            range: None,
+            params: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn picks_gzip_when_listed_with_a_quality_value() {
+        let req = request_with_accept_encoding("gzip;q=1.0, deflate;q=0.5");
+        assert_eq!(accepted_encoding(&req), Some("gzip"));
+    }
+
+    #[test]
+    fn falls_back_to_deflate_when_gzip_absent() {
+        let req = request_with_accept_encoding("deflate;q=0.5");
+        assert_eq!(accepted_encoding(&req), Some("deflate"));
+    }
+
+    #[test]
+    fn none_when_neither_encoding_is_listed() {
+        let req = request_with_accept_encoding("br;q=1.0");
+        assert_eq!(accepted_encoding(&req), None);
+    }
+}
+
+fn compress(bytes: &[u8], encoding: &str) -> Option<Vec<u8>> {
+    match encoding {
+        "gzip" => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(bytes).ok()?;
+            encoder.finish().ok()
+        }
+        "deflate" => {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(bytes).ok()?;
+            encoder.finish().ok()
+        }
+        _ => None,
+    }
+}
+
+/// Resolves a `RequestRange` against a known body length, returning the
+/// concrete inclusive `(start, end)` byte bounds, or `None` if the range is
+/// unsatisfiable for that length.
+fn resolve_range(range: RequestRange, total: usize) -> Option<(usize, usize)> {
+    match range {
+        RequestRange::Full(start, end) => {
+            if start >= total || start > end {
+                None
+            } else {
+                Some((start, end.min(total - 1)))
+            }
+        }
+        RequestRange::From(start) => {
+            if start >= total {
+                None
+            } else {
+                Some((start, total - 1))
+            }
+        }
+        RequestRange::Suffix(len) => {
+            if total == 0 || len == 0 {
+                None
+            } else {
+                let len = len.min(total);
+                Some((total - len, total - 1))
+            }
         }
-        response.push_str("\r\n");
-        response.push_str(&self.body);
-        let _ = stream.write_all(response.as_bytes());
     }
 }
 
-fn read_file_content(path: &str) -> io::Result<String> {
-    let mut file = File::open(path)?;
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
-    Ok(contents)
+#[cfg(test)]
+mod range_tests {
+    use super::*;
+
+    #[test]
+    fn parses_full_from_and_suffix_ranges() {
+        assert!(matches!(parse_range("bytes=0-499"), Some(RequestRange::Full(0, 499))));
+        assert!(matches!(parse_range("bytes=500-"), Some(RequestRange::From(500))));
+        assert!(matches!(parse_range("bytes=-500"), Some(RequestRange::Suffix(500))));
+    }
+
+    #[test]
+    fn rejects_malformed_range_headers() {
+        assert!(parse_range("bytes=abc-def").is_none());
+        assert!(parse_range("items=0-499").is_none());
+    }
+
+    #[test]
+    fn resolves_full_range_within_bounds() {
+        assert_eq!(resolve_range(RequestRange::Full(0, 499), 1000), Some((0, 499)));
+    }
+
+    #[test]
+    fn clamps_full_range_end_to_last_byte() {
+        assert_eq!(resolve_range(RequestRange::Full(0, 9999), 1000), Some((0, 999)));
+    }
+
+    #[test]
+    fn resolves_from_range_to_end_of_file() {
+        assert_eq!(resolve_range(RequestRange::From(900), 1000), Some((900, 999)));
+    }
+
+    #[test]
+    fn rejects_start_past_eof() {
+        assert_eq!(resolve_range(RequestRange::Full(1000, 1001), 1000), None);
+        assert_eq!(resolve_range(RequestRange::From(1000), 1000), None);
+    }
+
+    #[test]
+    fn resolves_suffix_range() {
+        assert_eq!(resolve_range(RequestRange::Suffix(100), 1000), Some((900, 999)));
+    }
+
+    #[test]
+    fn clamps_suffix_longer_than_file_to_whole_file() {
+        assert_eq!(resolve_range(RequestRange::Suffix(5000), 1000), Some((0, 999)));
+    }
+
+    #[test]
+    fn rejects_zero_length_suffix_range() {
+        assert_eq!(resolve_range(RequestRange::Suffix(0), 1000), None);
+        assert_eq!(resolve_range(RequestRange::Suffix(0), 0), None);
+    }
 }
 
-fn create_file(path: &str, content: &str) -> io::Result<usize> {
+fn create_file(path: &str, content: &[u8]) -> io::Result<()> {
     let mut file = File::create(path)?;
-    file.write(content.as_bytes())
+    file.write_all(content)
+}
+
+/// Joins a `/files/*path`-captured path onto `base_path`, rejecting any `..`
+/// or absolute component so a request can't escape the served directory
+/// (e.g. `/files/../../etc/passwd` or a leading-`/` component).
+fn resolve_served_path(base_path: &str, requested: &str) -> Option<PathBuf> {
+    let mut joined = PathBuf::from(base_path);
+    for component in Path::new(requested).components() {
+        match component {
+            Component::Normal(part) => joined.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(joined)
+}
+
+#[cfg(test)]
+mod served_path_tests {
+    use super::*;
+
+    #[test]
+    fn joins_plain_relative_path_onto_base() {
+        assert_eq!(
+            resolve_served_path("/srv/files", "report.txt"),
+            Some(PathBuf::from("/srv/files/report.txt"))
+        );
+        assert_eq!(
+            resolve_served_path("/srv/files", "sub/report.txt"),
+            Some(PathBuf::from("/srv/files/sub/report.txt"))
+        );
+    }
+
+    #[test]
+    fn rejects_parent_dir_traversal() {
+        assert_eq!(resolve_served_path("/srv/files", "../etc/passwd"), None);
+        assert_eq!(resolve_served_path("/srv/files", "a/../../etc/passwd"), None);
+        assert_eq!(
+            resolve_served_path("/srv/files", "../../../../../../etc/passwd"),
+            None
+        );
+    }
+
+    #[test]
+    fn rejects_absolute_path_component() {
+        assert_eq!(resolve_served_path("/srv/files", "/etc/passwd"), None);
+    }
+}
+
+// === Router ===
+type Handler = fn(&HTTPHandler, &Request) -> Response;
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Static(String),
+    Param(String),
+    Wildcard(String),
+}
+
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+    pattern
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            if let Some(name) = s.strip_prefix(':') {
+                Segment::Param(name.to_string())
+            } else if let Some(name) = s.strip_prefix('*') {
+                Segment::Wildcard(name.to_string())
+            } else {
+                Segment::Static(s.to_string())
+            }
+        })
+        .collect()
+}
+
+/// Lower is more specific: static segments win over `:param`, which wins
+/// over `*wildcard`.
+fn specificity(segments: &[Segment]) -> u32 {
+    segments
+        .iter()
+        .map(|s| match s {
+            Segment::Static(_) => 0,
+            Segment::Param(_) => 1,
+            Segment::Wildcard(_) => 2,
+        })
+        .sum()
+}
+
+fn match_segments(pattern: &[Segment], path: &[String]) -> Option<HashMap<String, String>> {
+    let mut params = HashMap::new();
+    for (i, segment) in pattern.iter().enumerate() {
+        match segment {
+            Segment::Wildcard(name) => {
+                if i >= path.len() {
+                    return None;
+                }
+                params.insert(name.clone(), path[i..].join("/"));
+                return Some(params);
+            }
+            Segment::Static(expected) => {
+                if path.get(i) != Some(expected) {
+                    return None;
+                }
+            }
+            Segment::Param(name) => {
+                params.insert(name.clone(), path.get(i)?.clone());
+            }
+        }
+    }
+    if pattern.len() == path.len() {
+        Some(params)
+    } else {
+        None
+    }
+}
+
+struct Route {
+    method: String,
+    segments: Vec<Segment>,
+    handler: Handler,
+}
+
+pub enum RouteMatch {
+    Matched {
+        handler: Handler,
+        params: HashMap<String, String>,
+    },
+    MethodNotAllowed,
+    NotFound,
+}
+
+/// A `route-recognizer`-style router: patterns like `/echo/:text` or
+/// `/files/*path` are registered per method, and matching a path yields the
+/// most specific route along with its captured params.
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self { routes: Vec::new() }
+    }
+
+    pub fn register(&mut self, method: &str, pattern: &str, handler: Handler) {
+        self.routes.push(Route {
+            method: method.to_string(),
+            segments: parse_pattern(pattern),
+            handler,
+        });
+    }
+
+    pub fn route(&self, method: &str, path: &[String]) -> RouteMatch {
+        let mut path_matched = false;
+        let mut best: Option<(u32, Handler, HashMap<String, String>)> = None;
+
+        for route in &self.routes {
+            let Some(params) = match_segments(&route.segments, path) else {
+                continue;
+            };
+            if route.method != method {
+                path_matched = true;
+                continue;
+            }
+            let score = specificity(&route.segments);
+            if best.as_ref().map_or(true, |(best_score, _, _)| score < *best_score) {
+                best = Some((score, route.handler, params));
+            }
+        }
+
+        match best {
+            Some((_, handler, params)) => RouteMatch::Matched { handler, params },
+            None if path_matched => RouteMatch::MethodNotAllowed,
+            None => RouteMatch::NotFound,
+        }
+    }
+}
+
+#[cfg(test)]
+mod router_tests {
+    use super::*;
+
+    fn segments(path: &str) -> Vec<String> {
+        path.split('/').filter(|s| !s.is_empty()).map(str::to_string).collect()
+    }
+
+    fn handler_static(_: &HTTPHandler, _: &Request) -> Response {
+        Response::new("200 OK", "static")
+    }
+
+    fn handler_param(_: &HTTPHandler, _: &Request) -> Response {
+        Response::new("200 OK", "param")
+    }
+
+    fn handler_wildcard(_: &HTTPHandler, _: &Request) -> Response {
+        Response::new("200 OK", "wildcard")
+    }
+
+    #[test]
+    fn static_route_beats_param_route() {
+        let mut router = Router::new();
+        router.register("GET", "/files/:id", handler_param);
+        router.register("GET", "/files/report", handler_static);
+
+        match router.route("GET", &segments("/files/report")) {
+            RouteMatch::Matched { handler, .. } => assert!(std::ptr::fn_addr_eq(handler, handler_static as Handler)),
+            _ => panic!("expected a match"),
+        }
+    }
+
+    #[test]
+    fn param_route_beats_wildcard_route() {
+        let mut router = Router::new();
+        router.register("GET", "/files/*path", handler_wildcard);
+        router.register("GET", "/files/:id", handler_param);
+
+        match router.route("GET", &segments("/files/report")) {
+            RouteMatch::Matched { handler, .. } => assert!(std::ptr::fn_addr_eq(handler, handler_param as Handler)),
+            _ => panic!("expected a match"),
+        }
+    }
+
+    #[test]
+    fn unknown_path_is_not_found() {
+        let mut router = Router::new();
+        router.register("GET", "/files/:id", handler_param);
+
+        assert!(matches!(router.route("GET", &segments("/other")), RouteMatch::NotFound));
+    }
+
+    #[test]
+    fn matched_path_with_wrong_method_is_method_not_allowed() {
+        let mut router = Router::new();
+        router.register("GET", "/files/:id", handler_param);
+
+        assert!(matches!(
+            router.route("POST", &segments("/files/report")),
+            RouteMatch::MethodNotAllowed
+        ));
+    }
 }
 
 struct HTTPHandler {
@@ -140,11 +803,10 @@ impl HTTPHandler {
     }
 
     fn handle_echo(&self, req: &Request) -> Response {
-        return if req.path.len() < 1 {
-            Response::new("400 Bad Request", "")
-        } else {
-            Response::new("200 OK", req.path.get(1).unwrap())
-        };
+        match req.params.get("text") {
+            Some(text) => Response::new("200 OK", text),
+            None => Response::new("400 Bad Request", ""),
+        }
     }
 
     fn handle_user_agent(&self, req: &Request) -> Response {
@@ -159,86 +821,231 @@ impl HTTPHandler {
     }
 
     fn handle_file(&self, req: &Request) -> Response {
+        let Some(path) = req.params.get("path") else {
+            return Response::new("400 Bad Request", "");
+        };
+        let Some(joined) = resolve_served_path(&self.base_path, path) else {
+            return Response::new("400 Bad Request", "");
+        };
+
         match req.method.as_str() {
-            "POST" => {
-                if req.path.len() < 1 {
-                    Response::new("400 Bad Request", "")
-                } else {
-                    let base = PathBuf::from(self.base_path.to_owned());
-                    let joined = base.join(req.path.get(1).unwrap());
-
-                    match create_file(joined.to_str().unwrap(), &req.body) {
-                        io::Result::Ok(_) => Response::new("201 Created", ""),
-                        io::Result::Err(error) => {
-                            Response::new("400 Bad Request", &format!("{:?}", error))
-                        }
-                    }
-                }
+            "POST" => match create_file(joined.to_str().unwrap(), &req.body) {
+                io::Result::Ok(_) => Response::new("201 Created", ""),
+                io::Result::Err(error) => Response::new("400 Bad Request", &format!("{:?}", error)),
+            },
+            "GET" => self.build_file_response(joined.to_str().unwrap(), req.range),
+            _ => Response::new("405 Method Not Allowed", ""),
+        }
+    }
+
+    /// Opens `path` and returns a response that streams it (or the requested
+    /// byte range of it) straight from disk, without reading the whole file
+    /// into memory here. A full-file body that `maybe_compress` later
+    /// compresses is read into memory whole.
+    fn build_file_response(&self, path: &str, range: Option<RequestRange>) -> Response {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(error) => return Response::new("400 Bad Request", &format!("{:?}", error)),
+        };
+        let total = match file.metadata() {
+            Ok(meta) => meta.len() as usize,
+            Err(error) => return Response::new("400 Bad Request", &format!("{:?}", error)),
+        };
+
+        let range = match range {
+            None => {
+                let mut resp = self.stream_file_range(file, 0, total, total, "200 OK", false);
+                resp.set_compressible(!is_precompressed(path));
+                return resp;
             }
-            "GET" => {
-                if req.path.len() < 1 {
-                    Response::new("400 Bad Request", "")
-                } else {
-                    let base = PathBuf::from(self.base_path.to_owned());
-                    let joined = base.join(req.path.get(1).unwrap());
-                    match read_file_content(joined.to_str().unwrap()) {
-                        io::Result::Ok(content) => {
-                            let mut resp = Response::new("200 OK", &content);
-                            resp.add_header(
-                                "Content-Type".to_owned(),
-                                "application/octet-stream".to_owned(),
-                            );
-                            resp
-                        }
-                        io::Result::Err(error) => {
-                            Response::new("400 Bad Request", &format!("{:?}", error))
-                        }
-                    }
-                }
+            Some(range) => range,
+        };
+
+        match resolve_range(range, total) {
+            Some((start, end)) => {
+                self.stream_file_range(file, start, end - start + 1, total, "206 Partial Content", true)
             }
-            _ => Response::new("405 Method Not Allowed", ""),
+            None => {
+                let mut resp = Response::new("416 Range Not Satisfiable", "");
+                resp.add_header("Content-Range".to_owned(), format!("bytes */{}", total));
+                resp
+            }
+        }
+    }
+
+    fn stream_file_range(
+        &self,
+        mut file: File,
+        start: usize,
+        len: usize,
+        total: usize,
+        status: &str,
+        partial: bool,
+    ) -> Response {
+        if start > 0 {
+            if let Err(error) = file.seek(SeekFrom::Start(start as u64)) {
+                return Response::new("500 Internal Server Error", &format!("{:?}", error));
+            }
+        }
+
+        let mut resp = Response::new_file(status, file, len);
+        resp.add_header("Accept-Ranges".to_owned(), "bytes".to_owned());
+        if partial {
+            resp.add_header(
+                "Content-Range".to_owned(),
+                format!("bytes {}-{}/{}", start, start + len.saturating_sub(1), total),
+            );
         }
+        resp
     }
 }
+fn default_worker_count() -> usize {
+    thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
 pub struct Args {
     #[arg(long, default_value = "./")]
     pub dir: String,
+
+    #[arg(long, default_value_t = default_worker_count())]
+    pub workers: usize,
+}
+
+fn panic_message(payload: &Box<dyn std::any::Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+// === ThreadPool ===
+enum PoolMessage {
+    Connection(TcpStream),
+    Terminate,
+}
+
+struct Worker {
+    id: usize,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+/// A fixed-size pool of worker threads that pull connections off a shared
+/// channel, so a flood of clients reuses threads instead of spawning one per
+/// connection.
+struct ThreadPool {
+    workers: Vec<Worker>,
+    sender: mpsc::Sender<PoolMessage>,
+}
+
+impl ThreadPool {
+    fn new(size: usize, server: Arc<Server>) -> Self {
+        assert!(size > 0);
+
+        let (sender, receiver) = mpsc::channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let mut workers = Vec::with_capacity(size);
+
+        for id in 0..size {
+            let receiver = Arc::clone(&receiver);
+            let server = Arc::clone(&server);
+            let thread = thread::spawn(move || loop {
+                let message = receiver.lock().unwrap().recv();
+                match message {
+                    Ok(PoolMessage::Connection(stream)) => {
+                        // Isolate one bad request from the rest of the pool:
+                        // a panic here must not permanently kill this worker.
+                        let server = Arc::clone(&server);
+                        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                            server.handle_connection(stream);
+                        }));
+                        if let Err(payload) = result {
+                            eprintln!(
+                                "Worker {} recovered from a panic while handling a connection: {}",
+                                id,
+                                panic_message(&payload)
+                            );
+                        }
+                    }
+                    Ok(PoolMessage::Terminate) | Err(_) => break,
+                }
+            });
+            workers.push(Worker {
+                id,
+                thread: Some(thread),
+            });
+        }
+
+        Self { workers, sender }
+    }
+
+    fn dispatch(&self, stream: TcpStream) {
+        let _ = self.sender.send(PoolMessage::Connection(stream));
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        for _ in &self.workers {
+            let _ = self.sender.send(PoolMessage::Terminate);
+        }
+
+        for worker in &mut self.workers {
+            if let Some(thread) = worker.thread.take() {
+                println!("Shutting down worker {}", worker.id);
+                let _ = thread.join();
+            }
+        }
+    }
 }
 
 struct Server {
     handler: HTTPHandler,
+    router: Router,
 }
 
 impl Server {
     fn new(args: Args) -> Self {
+        let mut router = Router::new();
+        router.register("GET", "/", HTTPHandler::handle_root as Handler);
+        router.register("GET", "/echo/:text", HTTPHandler::handle_echo as Handler);
+        router.register("GET", "/user-agent", HTTPHandler::handle_user_agent as Handler);
+        router.register("GET", "/files/*path", HTTPHandler::handle_file as Handler);
+        router.register("POST", "/files/*path", HTTPHandler::handle_file as Handler);
+
         Self {
             handler: HTTPHandler::new(args.dir.clone()),
+            router,
         }
     }
 
-    fn start_server(self: Arc<Self>) {
+    fn start_server(self: Arc<Self>, workers: usize) {
         let listener = TcpListener::bind("127.0.0.1:4221").unwrap();
         println!("Listening on http://127.0.0.1:4221");
 
+        let pool = ThreadPool::new(workers, Arc::clone(&self));
+
         for stream in listener.incoming() {
             if let Ok(stream) = stream {
-                let server = Arc::clone(&self); // clone the Arc, not the Server itself
-                thread::spawn(move || {
-                    server.handle_connection(stream);
-                });
+                pool.dispatch(stream);
             }
         }
     }
 
-    fn dispatch(&self, req: Request) -> Response {
-        match req.path.get(0).unwrap_or(&"".to_string()).as_str() {
-            "" => self.handler.handle_root(&req),
-            "echo" => self.handler.handle_echo(&req),
-            "user-agent" => self.handler.handle_user_agent(&req),
-            "files" => self.handler.handle_file(&req),
-            _ => self.handler.handle_not_found(),
+    fn dispatch(&self, req: &mut Request) -> Response {
+        match self.router.route(&req.method, &req.path) {
+            RouteMatch::Matched { handler, params } => {
+                req.params = params;
+                handler(&self.handler, req)
+            }
+            RouteMatch::MethodNotAllowed => Response::new("405 Method Not Allowed", ""),
+            RouteMatch::NotFound => self.handler.handle_not_found(),
         }
     }
 
@@ -247,14 +1054,15 @@ impl Server {
         let writer = stream.try_clone().unwrap();
 
         loop {
-            if let Some(request) = Request::from_stream(&stream) {
+            if let Some(mut request) = Request::from_stream(&stream) {
                 let connection_close = request
                     .headers
                     .get("Connection")
                     .map_or(false, |v| v == "close");
 
                 println!("Request: {:?}", request);
-                let response = self.dispatch(request);
+                let mut response = self.dispatch(&mut request);
+                response.maybe_compress(&request);
                 println!("Response: {:?}", response);
                 response.send(&writer);
 
@@ -272,7 +1080,8 @@ impl Server {
 fn main() {
     let args = Args::parse();
     println!("Args: {:?}", args);
+    let workers = args.workers;
 
     let server = Arc::new(Server::new(args));
-    server.start_server();
+    server.start_server(workers);
 }